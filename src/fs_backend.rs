@@ -0,0 +1,260 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// a single entry returned by `FileSystem::read_dir`, abstracted away from `std::fs::DirEntry`
+/// so the traversal/grouping logic can run against an in-memory backend in tests
+pub struct DirEntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub is_file: bool,
+}
+
+/// the subset of file metadata `fhcleanup` actually needs
+pub struct FileMetadata {
+    pub len: u64,
+}
+
+/// the filesystem operations `fhcleanup` needs to traverse a directory tree and to rename,
+/// move or delete the files it finds, abstracted so the grouping/retention/trim logic in
+/// `main.rs` can be unit-tested against an in-memory backend instead of scratch directories
+pub trait FileSystem: Send + Sync {
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>>;
+    fn create_dir_all(&self, path: &str) -> io::Result<()>;
+    fn remove_file(&self, path: &str) -> io::Result<()>;
+    fn exists(&self, path: &str) -> bool;
+    fn metadata(&self, path: &str) -> io::Result<FileMetadata>;
+
+    /// atomically moves `source` to `target`, failing with `io::ErrorKind::AlreadyExists`
+    /// instead of clobbering an existing file at `target`
+    fn rename_no_replace(&self, source: &str, target: &str) -> io::Result<()>;
+}
+
+/// `FileSystem` backed by the real `std::fs`
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>> {
+        let mut entries = Vec::new();
+        for dir_elem in fs::read_dir(path)? {
+            match dir_elem {
+                Ok(dir_elem) => match dir_elem.file_type() {
+                    Ok(file_type) => {
+                        let name = match dir_elem.file_name().into_string() {
+                            Ok(name) => name,
+                            Err(name) => {
+                                eprintln!("Skipping invalid UTF-8 file name: '{:?}'", name);
+                                continue;
+                            }
+                        };
+                        entries.push(DirEntryInfo {
+                            name,
+                            is_dir: file_type.is_dir(),
+                            is_file: file_type.is_file(),
+                        });
+                    }
+                    Err(e) => eprintln!(
+                        "Could not determine file type of {:?}: {}",
+                        dir_elem.path(),
+                        e
+                    ),
+                },
+                Err(e) => eprintln!("could not read dir element: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn metadata(&self, path: &str) -> io::Result<FileMetadata> {
+        fs::metadata(path).map(|m| FileMetadata { len: m.len() })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn rename_no_replace(&self, source: &str, target: &str) -> io::Result<()> {
+        use nix::fcntl::{renameat2, RenameFlags};
+
+        renameat2(None, source, None, target, RenameFlags::RENAME_NOREPLACE)
+            .map_err(io::Error::from)
+    }
+
+    /// platforms without `renameat2` have no single syscall for this, so the destination
+    /// name is claimed exclusively first and only then is the source renamed over that
+    /// placeholder, which is always safe since we just created it ourselves
+    #[cfg(not(target_os = "linux"))]
+    fn rename_no_replace(&self, source: &str, target: &str) -> io::Result<()> {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(target)?;
+        fs::rename(source, target)
+    }
+}
+
+/// moves `source` into `target_dir` under `file_name`, appending a numeric suffix
+/// (`name (1).ext`, `name (2).ext`, ...) on collision so a moved file is never lost
+pub fn mov_to_unique_target(
+    fs: &dyn FileSystem,
+    source: &str,
+    target_dir: &str,
+    file_name: &str,
+) -> io::Result<String> {
+    let mut candidate = target_dir.to_string() + file_name;
+    let mut suffix = 0u32;
+
+    loop {
+        match fs.rename_no_replace(source, &candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                suffix += 1;
+                candidate = target_dir.to_string() + &suffixed_name(file_name, suffix);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn suffixed_name(file_name: &str, suffix: u32) -> String {
+    match file_name.rfind('.') {
+        Some(idx) => format!("{} ({}){}", &file_name[..idx], suffix, &file_name[idx..]),
+        None => format!("{} ({})", file_name, suffix),
+    }
+}
+
+/// in-memory `FileSystem` fake used by tests to assert exactly which files a given `Opt`
+/// configuration would rename, move or delete, without touching real disk
+#[cfg(test)]
+pub mod mem {
+    use super::{DirEntryInfo, FileMetadata, FileSystem};
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::Mutex;
+
+    enum Entry {
+        File { size: u64 },
+        Dir,
+    }
+
+    #[derive(Default)]
+    pub struct MemFileSystem {
+        entries: Mutex<HashMap<String, Entry>>,
+    }
+
+    impl MemFileSystem {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn with_file(self, path: &str, size: u64) -> Self {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), Entry::File { size });
+            self
+        }
+
+        /// adds a file after construction, e.g. to simulate a later run of `fhcleanup`
+        /// finding a new duplicate on top of state left behind by an earlier one
+        pub fn add_file(&self, path: &str, size: u64) {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), Entry::File { size });
+        }
+
+        pub fn with_dir(self, path: &str) -> Self {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), Entry::Dir);
+            self
+        }
+
+        pub fn contains(&self, path: &str) -> bool {
+            self.entries.lock().unwrap().contains_key(path)
+        }
+    }
+
+    impl FileSystem for MemFileSystem {
+        fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntryInfo>> {
+            let entries = self.entries.lock().unwrap();
+            let mut result = Vec::new();
+
+            for (full_path, entry) in entries.iter() {
+                if let Some(rest) = full_path.strip_prefix(path) {
+                    if rest.is_empty() {
+                        continue;
+                    }
+                    let rest = rest.trim_end_matches('/');
+                    if rest.is_empty() || rest.contains('/') {
+                        // not a direct child of `path`
+                        continue;
+                    }
+
+                    let is_dir = matches!(entry, Entry::Dir);
+                    result.push(DirEntryInfo {
+                        name: rest.to_string(),
+                        is_dir,
+                        is_file: !is_dir,
+                    });
+                }
+            }
+
+            Ok(result)
+        }
+
+        fn create_dir_all(&self, path: &str) -> io::Result<()> {
+            self.entries
+                .lock()
+                .unwrap()
+                .entry(path.to_string())
+                .or_insert(Entry::Dir);
+            Ok(())
+        }
+
+        fn remove_file(&self, path: &str) -> io::Result<()> {
+            self.entries
+                .lock()
+                .unwrap()
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file missing"))
+        }
+
+        fn exists(&self, path: &str) -> bool {
+            self.entries.lock().unwrap().contains_key(path)
+        }
+
+        fn metadata(&self, path: &str) -> io::Result<FileMetadata> {
+            match self.entries.lock().unwrap().get(path) {
+                Some(Entry::File { size }) => Ok(FileMetadata { len: *size }),
+                Some(Entry::Dir) => Ok(FileMetadata { len: 0 }),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+            }
+        }
+
+        fn rename_no_replace(&self, source: &str, target: &str) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.contains_key(target) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "target exists"));
+            }
+
+            let entry = entries
+                .remove(source)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "source missing"))?;
+            entries.insert(target.to_string(), entry);
+            Ok(())
+        }
+    }
+}
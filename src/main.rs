@@ -1,16 +1,28 @@
 use chrono::NaiveDateTime;
+use fs_backend::{DirEntryInfo, FileSystem, StdFileSystem};
 use lazy_static::lazy_static;
+use progress::ProgressReporter;
 use regex::Regex;
 use rusty_pool::{Builder, ThreadPool};
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc,
 };
 use std::time::SystemTime;
-use std::{fs, fs::DirEntry};
 use structopt::StructOpt;
+use twox_hash::XxHash64;
+
+mod fs_backend;
+mod progress;
+
+/// number of leading bytes hashed as a cheap pre-filter before committing to a full read
+const PARTIAL_HASH_SIZE: u64 = 16 * 1024;
 
 /// Clear Windows file history files by finding files with the same name except for a UTC timestamp
 /// within the same directory and keeping the latest version of the file, triming the timestamp
@@ -42,23 +54,114 @@ struct Opt {
     #[structopt(short = "n", long)]
     keep_names: bool,
 
+    /// the number of most recent versions of each file to retain instead of purging all
+    /// but the latest, defaults to 1; when the file without timestamp already exists,
+    /// all timestamped versions are purged or moved regardless of this value
+    #[structopt(short = "k", long, default_value = "1")]
+    keep: u32,
+
+    /// preview the actions fhcleanup would take without touching the filesystem,
+    /// logging each rename/move/delete that would have happened when -v is set
+    #[structopt(short = "d", long)]
+    dry_run: bool,
+
+    /// only treat files sharing a trimmed name as duplicates if their contents are
+    /// byte-identical to the kept version (the existing file without timestamp if there is
+    /// one, otherwise the newest version), instead of trusting the file name alone
+    #[structopt(long)]
+    verify_hash: bool,
+
+    /// comma-separated, case-insensitive list of extensions to exclusively process,
+    /// e.g. `docx,xlsx`; takes precedence over --exclude-ext when both are set
+    #[structopt(long)]
+    include_ext: Option<String>,
+
+    /// comma-separated, case-insensitive list of extensions to skip,
+    /// e.g. `tmp,log`; ignored when --include-ext is set
+    #[structopt(long)]
+    exclude_ext: Option<String>,
+
+    /// show a live single-line status (dirs entered, files scanned, moved/deleted/renamed
+    /// totals) while the run is in progress, useful for large recursive sweeps;
+    /// the spinner is suppressed when -v logging is active since both write to the
+    /// same terminal line
+    #[structopt(long)]
+    progress: bool,
+
     /// Verbose mode (-v, -vv, -vvv, etc.)
     #[structopt(short, long, parse(from_occurrences))]
     verbose: u8,
 }
 
+impl Opt {
+    /// whether a file with the given (trimmed) extension should be processed,
+    /// according to --include-ext/--exclude-ext
+    fn extension_allowed(&self, extension: &str) -> bool {
+        if let Some(include) = &self.include_ext {
+            return ext_list_contains(include, extension);
+        }
+
+        if let Some(exclude) = &self.exclude_ext {
+            return !ext_list_contains(exclude, extension);
+        }
+
+        true
+    }
+}
+
+fn ext_list_contains(list: &str, extension: &str) -> bool {
+    list.split(',')
+        .any(|candidate| candidate.trim().eq_ignore_ascii_case(extension))
+}
+
 struct FHFile {
     date: NaiveDateTime,
     dir_path: Option<String>,
     full_name: String,
+    size: u64,
+    hash: RefCell<Option<u64>>,
 }
 
 impl FHFile {
-    fn rename(self, trimmed_name: &str, verbosity_level: u8) {
+    fn full_path(&self) -> String {
+        self.dir_path.as_deref().unwrap_or("./").to_string() + self.full_name.as_str()
+    }
+
+    /// hash of the leading `PARTIAL_HASH_SIZE` bytes, used as a cheap pre-filter before
+    /// committing to a full read
+    fn partial_hash(&self) -> std::io::Result<u64> {
+        hash_file(&self.full_path(), PARTIAL_HASH_SIZE)
+    }
+
+    /// full content hash, computed lazily on first use and cached on the file
+    fn full_hash(&self) -> std::io::Result<u64> {
+        if let Some(hash) = *self.hash.borrow() {
+            return Ok(hash);
+        }
+
+        let hash = hash_file(&self.full_path(), u64::MAX)?;
+        *self.hash.borrow_mut() = Some(hash);
+        Ok(hash)
+    }
+
+    fn rename(
+        self,
+        trimmed_name: &str,
+        verbosity_level: u8,
+        dry_run: bool,
+        fs: &dyn FileSystem,
+        progress: &Option<ProgressReporter>,
+    ) {
         let dir_path = self.dir_path.unwrap_or_else(|| String::from("./"));
         let source_name = dir_path.clone() + self.full_name.as_str();
         let target_name = dir_path + trimmed_name;
-        if let Err(e) = fs::rename(&source_name, &target_name) {
+
+        if dry_run {
+            RENAMED_COUNT.fetch_add(1, Ordering::SeqCst);
+            if verbosity_level >= 1 {
+                println!("Would rename '{}' to '{}'", source_name, target_name);
+            }
+        } else if let Err(e) = fs.rename_no_replace(&source_name, &target_name) {
             eprintln!("Could not rename '{}': {}", source_name, e);
         } else {
             RENAMED_COUNT.fetch_add(1, Ordering::SeqCst);
@@ -66,31 +169,77 @@ impl FHFile {
                 println!("Renamed '{}' to '{}'", source_name, target_name);
             }
         }
-    }
 
-    fn mov(self, temp_dir: String, verbosity_level: u8) {
-        let target_dir = temp_dir + self.dir_path.as_deref().unwrap_or("");
-        if let Err(e) = fs::create_dir_all(&target_dir) {
-            panic!("could not create to_delete folder '{}': {}", &target_dir, e);
+        if let Some(progress) = progress {
+            progress.file_acted_on();
         }
+    }
 
+    fn mov(
+        self,
+        temp_dir: String,
+        verbosity_level: u8,
+        dry_run: bool,
+        fs: &dyn FileSystem,
+        progress: &Option<ProgressReporter>,
+    ) {
+        let target_dir = temp_dir + self.dir_path.as_deref().unwrap_or("");
         let source_name =
             self.dir_path.unwrap_or_else(|| String::from("./")) + self.full_name.as_str();
-        let target_name = target_dir + self.full_name.as_str();
-        if let Err(e) = fs::rename(&source_name, &target_name) {
-            eprintln!("Could not mov '{}': {}", source_name, e);
-        } else {
+
+        if dry_run {
+            let target_name = target_dir + self.full_name.as_str();
             MOVED_COUNT.fetch_add(1, Ordering::SeqCst);
             if verbosity_level >= 1 {
-                println!("Moved '{}' to '{}'", source_name, target_name);
+                println!("Would move '{}' to '{}'", source_name, target_name);
             }
+            if let Some(progress) = progress {
+                progress.file_acted_on();
+            }
+            return;
+        }
+
+        if let Err(e) = fs.create_dir_all(&target_dir) {
+            panic!("could not create to_delete folder '{}': {}", &target_dir, e);
+        }
+
+        match fs_backend::mov_to_unique_target(fs, &source_name, &target_dir, &self.full_name) {
+            Ok(target_name) => {
+                MOVED_COUNT.fetch_add(1, Ordering::SeqCst);
+                if verbosity_level >= 1 {
+                    println!("Moved '{}' to '{}'", source_name, target_name);
+                }
+            }
+            Err(e) => eprintln!("Could not mov '{}': {}", source_name, e),
+        }
+
+        if let Some(progress) = progress {
+            progress.file_acted_on();
         }
     }
 
-    fn delete(self, verbosity_level: u8) {
+    fn delete(
+        self,
+        verbosity_level: u8,
+        dry_run: bool,
+        fs: &dyn FileSystem,
+        progress: &Option<ProgressReporter>,
+    ) {
         let source_name =
             self.dir_path.unwrap_or_else(|| String::from("./")) + self.full_name.as_str();
-        if let Err(e) = fs::remove_file(&source_name) {
+
+        if dry_run {
+            DELETED_COUNT.fetch_add(1, Ordering::SeqCst);
+            if verbosity_level >= 1 {
+                println!("Would delete '{}'", &source_name);
+            }
+            if let Some(progress) = progress {
+                progress.file_acted_on();
+            }
+            return;
+        }
+
+        if let Err(e) = fs.remove_file(&source_name) {
             eprintln!("Could not delete '{}': {}", source_name, e);
         } else {
             DELETED_COUNT.fetch_add(1, Ordering::SeqCst);
@@ -98,6 +247,10 @@ impl FHFile {
                 println!("Deleted '{}'", &source_name);
             }
         }
+
+        if let Some(progress) = progress {
+            progress.file_acted_on();
+        }
     }
 }
 
@@ -133,6 +286,15 @@ fn main() {
     }
 
     let opt = Arc::new(opt);
+    let fs: Arc<dyn FileSystem> = Arc::new(StdFileSystem);
+
+    let progress = if opt.progress {
+        let (reporter, join_handle) = progress::spawn(opt.verbose == 0);
+        Some((reporter, join_handle))
+    } else {
+        None
+    };
+    let reporter = progress.as_ref().map(|(reporter, _)| reporter.clone());
 
     let pool = if let Some(max_size) = opt.max_threads {
         Builder::new().max_size(max_size).build()
@@ -140,12 +302,26 @@ fn main() {
         ThreadPool::default()
     };
 
-    let cloned_pool = pool.clone();
-    pool.execute(|| handle_dir(None, opt, cloned_pool));
+    let ctx = RunContext {
+        opt: opt.clone(),
+        fs,
+        pool: pool.clone(),
+        progress: reporter,
+    };
+    pool.execute(move || handle_dir(None, ctx));
     pool.join();
 
+    if let Some((reporter, join_handle)) = progress {
+        drop(reporter);
+        let _ = join_handle.join();
+    }
+
     println!("__________________________________________________________");
 
+    if opt.dry_run {
+        println!("Dry run - no files were actually renamed, moved or deleted");
+    }
+
     let moved_count = MOVED_COUNT.load(Ordering::SeqCst);
     let deleted_count = DELETED_COUNT.load(Ordering::SeqCst);
     let renamed_count = RENAMED_COUNT.load(Ordering::SeqCst);
@@ -170,33 +346,43 @@ fn main() {
     }
 }
 
-fn handle_dir(path: Option<String>, opt: Arc<Opt>, pool: ThreadPool) {
+/// the state that needs to flow into every worker task spawned while recursing through
+/// subdirectories, bundled up so `handle_dir`/`handle_dir_elem` don't have to take each
+/// piece as its own parameter
+#[derive(Clone)]
+struct RunContext {
+    opt: Arc<Opt>,
+    fs: Arc<dyn FileSystem>,
+    pool: ThreadPool,
+    progress: Option<ProgressReporter>,
+}
+
+fn handle_dir(path: Option<String>, ctx: RunContext) {
     let current_path = path.clone().unwrap_or_else(|| String::from("./"));
-    match fs::read_dir(&current_path) {
+    match ctx.fs.read_dir(&current_path) {
         Ok(dir_elems) => {
             let mut fh_files_map: HashMap<String, Vec<FHFile>> = HashMap::new();
-            let verbosity_level = opt.verbose;
+            let verbosity_level = ctx.opt.verbose;
             if verbosity_level >= 2 {
                 println!("stepping into dir: {}", &current_path);
             }
 
+            if let Some(progress) = &ctx.progress {
+                progress.dir_entered();
+            }
+
             for dir_elem in dir_elems {
-                match dir_elem {
-                    Ok(dir_elem) => {
-                        handle_dir_elem(
-                            dir_elem,
-                            &opt,
-                            &pool,
-                            &current_path,
-                            &mut fh_files_map,
-                            &path,
-                        );
-                    }
-                    Err(e) => eprintln!("could not read dir element: {}", e),
-                }
+                handle_dir_elem(dir_elem, &ctx, &current_path, &mut fh_files_map, &path);
             }
 
-            handle_results(fh_files_map, current_path, verbosity_level, opt);
+            handle_results(
+                fh_files_map,
+                current_path,
+                verbosity_level,
+                ctx.opt,
+                ctx.fs,
+                ctx.progress,
+            );
         }
         Err(e) => eprintln!("could not open dir '{}': {}", &current_path, e),
     }
@@ -204,85 +390,80 @@ fn handle_dir(path: Option<String>, opt: Arc<Opt>, pool: ThreadPool) {
 
 #[inline]
 fn handle_dir_elem(
-    dir_elem: DirEntry,
-    opt: &Arc<Opt>,
-    pool: &ThreadPool,
-    current_path: &String,
-    mut fh_files_map: &mut HashMap<String, Vec<FHFile>>,
+    dir_elem: DirEntryInfo,
+    ctx: &RunContext,
+    current_path: &str,
+    fh_files_map: &mut HashMap<String, Vec<FHFile>>,
     path: &Option<String>,
 ) {
-    let file_type = dir_elem.file_type();
-    match file_type {
-        Ok(file_type) => {
-            if opt.incl_subdir && file_type.is_dir() {
-                let cloned_pool = pool.clone();
-                let cloned_opt = opt.clone();
-                let current_path = current_path.clone();
-                pool.execute(move || {
-                    handle_dir(
-                        Some(
-                            current_path
-                                + dir_elem.file_name().to_str().unwrap_or_else(|| {
-                                    panic!("Invalid UTF-8 file name: '{:?}'", dir_elem.file_name())
-                                })
-                                + "/",
-                        ),
-                        cloned_opt,
-                        cloned_pool,
-                    )
-                });
-            } else if file_type.is_file() {
-                handle_file(dir_elem, &mut fh_files_map, path);
-            }
-        }
-        Err(e) => eprintln!(
-            "Could not determine file type of {:?}: {}",
-            dir_elem.path(),
-            e
-        ),
+    if ctx.opt.incl_subdir && dir_elem.is_dir {
+        let child_ctx = ctx.clone();
+        let current_path = current_path.to_owned();
+        ctx.pool.execute(move || {
+            handle_dir(Some(current_path + dir_elem.name.as_str() + "/"), child_ctx)
+        });
+    } else if dir_elem.is_file {
+        handle_file(dir_elem, &ctx.opt, &ctx.fs, fh_files_map, path, &ctx.progress);
     }
 }
 
 #[inline]
 fn handle_file(
-    dir_elem: DirEntry,
+    dir_elem: DirEntryInfo,
+    opt: &Arc<Opt>,
+    fs: &Arc<dyn FileSystem>,
     fh_files_map: &mut HashMap<String, Vec<FHFile>>,
     path: &Option<String>,
+    progress: &Option<ProgressReporter>,
 ) {
-    match dir_elem.file_name().to_str() {
-        Some(file_name) if FILE_NAME_REGEX.is_match(file_name) => {
-            let date_str = FILE_NAME_REGEX
-                .find_iter(file_name)
-                .last()
-                .expect("no last item found for regex despite is_match returning true")
-                .as_str();
-            let date = DATE_PART_REGEX
-                .find(date_str)
-                .unwrap_or_else(|| panic!("could not extract date from {}", date_str))
-                .as_str();
-            let parsed_date = NaiveDateTime::parse_from_str(date, "%Y_%m_%d %H_%M_%S")
-                .unwrap_or_else(|_| panic!("could not parse date: '{}'", date));
-
-            let mut parts = FILE_END_REGEX.split(file_name).collect::<Vec<&str>>();
-            let extension = parts.pop().expect("file parts empty");
-            let mut trimmed_name = parts
-                .into_iter()
-                .map(|part| part.trim())
-                .fold(String::new(), |a, b| a + b);
-            trimmed_name.push('.');
-            trimmed_name.push_str(extension);
-
-            let fh_file = FHFile {
-                date: parsed_date,
-                dir_path: path.clone(),
-                full_name: String::from(file_name),
-            };
-            put_multi_map(fh_files_map, trimmed_name, fh_file);
+    let file_name = dir_elem.name.as_str();
+    if FILE_NAME_REGEX.is_match(file_name) {
+        let date_str = FILE_NAME_REGEX
+            .find_iter(file_name)
+            .last()
+            .expect("no last item found for regex despite is_match returning true")
+            .as_str();
+        let date = DATE_PART_REGEX
+            .find(date_str)
+            .unwrap_or_else(|| panic!("could not extract date from {}", date_str))
+            .as_str();
+        let parsed_date = NaiveDateTime::parse_from_str(date, "%Y_%m_%d %H_%M_%S")
+            .unwrap_or_else(|_| panic!("could not parse date: '{}'", date));
+
+        let mut parts = FILE_END_REGEX.split(file_name).collect::<Vec<&str>>();
+        let extension = parts.pop().expect("file parts empty");
+
+        if !opt.extension_allowed(extension) {
+            return;
+        }
+
+        let mut trimmed_name = parts
+            .into_iter()
+            .map(|part| part.trim())
+            .fold(String::new(), |a, b| a + b);
+        trimmed_name.push('.');
+        trimmed_name.push_str(extension);
+
+        let full_path = path.as_deref().unwrap_or("./").to_string() + file_name;
+        let size = fs.metadata(&full_path).map(|m| m.len).unwrap_or_else(|e| {
+            eprintln!("Could not read metadata of '{}': {}", file_name, e);
+            0
+        });
+
+        let fh_file = FHFile {
+            date: parsed_date,
+            dir_path: path.clone(),
+            full_name: String::from(file_name),
+            size,
+            hash: RefCell::new(None),
+        };
+        put_multi_map(fh_files_map, trimmed_name, fh_file);
+
+        if let Some(progress) = progress {
+            progress.file_scanned();
         }
-        None => eprintln!("Invalid UTF-8 file name: '{:?}'", dir_elem.file_name()),
-        // irrelevant file name
-        Some(_) => {}
     }
+    // irrelevant file name otherwise
 }
 
 #[inline]
@@ -291,6 +472,8 @@ fn handle_results(
     current_path: String,
     verbosity_level: u8,
     opt: Arc<Opt>,
+    fs: Arc<dyn FileSystem>,
+    progress: Option<ProgressReporter>,
 ) {
     if fh_files_map.is_empty() {
         if verbosity_level >= 2 {
@@ -310,7 +493,7 @@ fn handle_results(
             let mut file_duplicates = file_entry.1;
 
             let trimmed_path = current_path.clone() + &trimmed_name;
-            let should_rename = if Path::new(&trimmed_path).exists() {
+            let should_rename = if fs.exists(&trimmed_path) {
                 if verbosity_level >= 1 {
                     println!("File without timestamp already exists, treating all other files as duplicates: {}", &trimmed_path);
                 }
@@ -330,13 +513,57 @@ fn handle_results(
                 );
             }
 
+            // the reference signature must come from the file that is actually kept: the
+            // pre-existing untimestamped file when one is already on disk, or otherwise the
+            // newest file found in this sweep (which is the one that will be kept/renamed)
+            let newest_signature = if !opt.verify_hash {
+                None
+            } else if should_rename {
+                file_duplicates.last().and_then(|newest| {
+                    match (newest.partial_hash(), newest.full_hash()) {
+                        (Ok(partial), Ok(full)) => Some((newest.size, partial, full)),
+                        (Err(e), _) | (_, Err(e)) => {
+                            eprintln!(
+                                "Could not hash '{}', skipping hash verification for this group: {}",
+                                newest.full_name, e
+                            );
+                            None
+                        }
+                    }
+                })
+            } else {
+                signature_of_existing_target(fs.as_ref(), &trimmed_path)
+            };
+
+            // when the untimestamped file already exists, every found copy is a duplicate
+            // of it; otherwise only the versions below the `--keep` cutoff get purged/moved
+            let cutoff = file_count.saturating_sub(opt.keep as usize);
+
             for (i, file) in file_duplicates.into_iter().enumerate() {
                 // rename last file
-                if should_rename && !opt.keep_names && i == file_count - 1 {
-                    file.rename(&trimmed_name, verbosity_level);
-                } else if !should_rename || i < file_count - 1 {
+                if should_rename && opt.keep == 1 && !opt.keep_names && i == file_count - 1 {
+                    file.rename(
+                        &trimmed_name,
+                        verbosity_level,
+                        opt.dry_run,
+                        fs.as_ref(),
+                        &progress,
+                    );
+                } else if !should_rename || i < cutoff {
+                    if let Some(newest_signature) = newest_signature {
+                        if !matches_newest(&file, newest_signature) {
+                            if verbosity_level >= 1 {
+                                println!(
+                                    "'{}' differs in content from the kept version, leaving untouched",
+                                    file.full_path()
+                                );
+                            }
+                            continue;
+                        }
+                    }
+
                     if opt.purge {
-                        file.delete(verbosity_level);
+                        file.delete(verbosity_level, opt.dry_run, fs.as_ref(), &progress);
                     } else {
                         let temp_dir = if let Some(ref dir) = opt.target_folder {
                             String::from(
@@ -347,7 +574,7 @@ fn handle_results(
                             String::from("./fhcleanup_to_del/")
                         };
 
-                        file.mov(temp_dir, verbosity_level);
+                        file.mov(temp_dir, verbosity_level, opt.dry_run, fs.as_ref(), &progress);
                     }
                 }
             }
@@ -355,6 +582,84 @@ fn handle_results(
     }
 }
 
+/// reads at most `limit` bytes from `path` and returns a fast, non-cryptographic digest
+/// of its contents
+fn hash_file(path: &str, limit: u64) -> std::io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = XxHash64::default();
+    let mut buf = [0u8; 8192];
+    let mut read_total = 0u64;
+
+    while read_total < limit {
+        let to_read = (buf.len() as u64).min(limit - read_total) as usize;
+        let n = file.read(&mut buf[..to_read])?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[..n]);
+        read_total += n as u64;
+    }
+
+    Ok(hasher.finish())
+}
+
+/// signature (size, partial hash, full hash) of the pre-existing untimestamped file at
+/// `trimmed_path`, used as the `--verify-hash` reference when that file already exists and
+/// is therefore the one being kept rather than any of the timestamped versions just found
+fn signature_of_existing_target(fs: &dyn FileSystem, trimmed_path: &str) -> Option<(u64, u64, u64)> {
+    let size = match fs.metadata(trimmed_path) {
+        Ok(metadata) => metadata.len,
+        Err(e) => {
+            eprintln!(
+                "Could not read metadata of '{}', skipping hash verification for this group: {}",
+                trimmed_path, e
+            );
+            return None;
+        }
+    };
+
+    match (
+        hash_file(trimmed_path, PARTIAL_HASH_SIZE),
+        hash_file(trimmed_path, u64::MAX),
+    ) {
+        (Ok(partial), Ok(full)) => Some((size, partial, full)),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!(
+                "Could not hash '{}', skipping hash verification for this group: {}",
+                trimmed_path, e
+            );
+            None
+        }
+    }
+}
+
+/// checks whether `file` is a byte-identical duplicate of the kept file of its group (the
+/// newest file found, or the pre-existing untimestamped file), using that file's
+/// already-computed size/partial-hash/full-hash as the reference
+fn matches_newest(file: &FHFile, newest: (u64, u64, u64)) -> bool {
+    let (newest_size, newest_partial, newest_full) = newest;
+    if file.size != newest_size {
+        return false;
+    }
+
+    match file.partial_hash() {
+        Ok(partial) if partial == newest_partial => {}
+        Ok(_) => return false,
+        Err(e) => {
+            eprintln!("Could not hash '{}': {}", file.full_name, e);
+            return false;
+        }
+    }
+
+    match file.full_hash() {
+        Ok(full) => full == newest_full,
+        Err(e) => {
+            eprintln!("Could not hash '{}': {}", file.full_name, e);
+            false
+        }
+    }
+}
+
 #[inline]
 fn put_multi_map(map: &mut HashMap<String, Vec<FHFile>>, key: String, elem: FHFile) {
     if let Some(vec) = map.get_mut(&key) {
@@ -363,3 +668,349 @@ fn put_multi_map(map: &mut HashMap<String, Vec<FHFile>>, key: String, elem: FHFi
         map.insert(key, vec![elem]);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs_backend::mem::MemFileSystem;
+    use std::path::Path;
+
+    fn test_opt() -> Opt {
+        Opt {
+            incl_subdir: false,
+            max_threads: None,
+            target_folder: None,
+            purge: false,
+            keep_names: false,
+            keep: 1,
+            dry_run: false,
+            verify_hash: false,
+            include_ext: None,
+            exclude_ext: None,
+            progress: false,
+            verbose: 0,
+        }
+    }
+
+    fn fh_file(dir_path: Option<&str>, full_name: &str, date: &str) -> FHFile {
+        FHFile {
+            date: NaiveDateTime::parse_from_str(date, "%Y_%m_%d %H_%M_%S").unwrap(),
+            dir_path: dir_path.map(String::from),
+            full_name: full_name.to_string(),
+            size: 1,
+            hash: RefCell::new(None),
+        }
+    }
+
+    fn run(
+        opt: Opt,
+        fs: Arc<dyn FileSystem>,
+        current_path: &str,
+        fh_files_map: HashMap<String, Vec<FHFile>>,
+    ) {
+        handle_results(
+            fh_files_map,
+            current_path.to_string(),
+            0,
+            Arc::new(opt),
+            fs,
+            None,
+        );
+    }
+
+    #[test]
+    fn keeps_newest_and_moves_older_to_default_target() {
+        let fs: Arc<dyn FileSystem> = Arc::new(
+            MemFileSystem::new()
+                .with_file(
+                    "./report (2023_01_01 10_00_00 UTC).txt",
+                    1,
+                )
+                .with_file(
+                    "./report (2023_06_01 10_00_00 UTC).txt",
+                    1,
+                ),
+        );
+
+        let mut fh_files_map = HashMap::new();
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_01_01 10_00_00 UTC).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_06_01 10_00_00 UTC).txt",
+                "2023_06_01 10_00_00",
+            ),
+        );
+
+        run(test_opt(), fs.clone(), "./", fh_files_map);
+
+        assert!(fs.exists("./report.txt"));
+        assert!(!fs.exists("./report (2023_06_01 10_00_00 UTC).txt"));
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_01_01 10_00_00 UTC).txt"));
+        assert!(!fs.exists("./report (2023_01_01 10_00_00 UTC).txt"));
+    }
+
+    #[test]
+    fn already_trimmed_target_treats_all_copies_as_duplicates() {
+        let fs: Arc<dyn FileSystem> = Arc::new(
+            MemFileSystem::new()
+                .with_file("./report.txt", 1)
+                .with_file("./report (2023_01_01 10_00_00 UTC).txt", 1)
+                .with_file("./report (2023_06_01 10_00_00 UTC).txt", 1),
+        );
+
+        let mut fh_files_map = HashMap::new();
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_01_01 10_00_00 UTC).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_06_01 10_00_00 UTC).txt",
+                "2023_06_01 10_00_00",
+            ),
+        );
+
+        run(test_opt(), fs.clone(), "./", fh_files_map);
+
+        // the pre-existing untimestamped file is never touched and both timestamped
+        // copies are treated as duplicates of it, including the newest one
+        assert!(fs.exists("./report.txt"));
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_01_01 10_00_00 UTC).txt"));
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_06_01 10_00_00 UTC).txt"));
+    }
+
+    #[test]
+    fn subdir_duplicates_move_into_mirrored_target_path() {
+        let mem = Arc::new(
+            MemFileSystem::new()
+                .with_dir("sub1/")
+                .with_file("sub1/report (2023_01_01 10_00_00 UTC).txt", 1)
+                .with_file("sub1/report (2023_06_01 10_00_00 UTC).txt", 1),
+        );
+        let fs: Arc<dyn FileSystem> = mem.clone();
+
+        let mut fh_files_map = HashMap::new();
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                Some("sub1/"),
+                "report (2023_01_01 10_00_00 UTC).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                Some("sub1/"),
+                "report (2023_06_01 10_00_00 UTC).txt",
+                "2023_06_01 10_00_00",
+            ),
+        );
+
+        run(test_opt(), fs.clone(), "sub1/", fh_files_map);
+
+        // the default target folder is always "./fhcleanup_to_del/" + the dir being
+        // processed, never a per-subdir folder nested under the subdir itself
+        assert!(fs.exists("sub1/report.txt"));
+        assert!(mem.contains("./fhcleanup_to_del/sub1/report (2023_01_01 10_00_00 UTC).txt"));
+    }
+
+    #[test]
+    fn mov_collision_in_to_delete_gets_a_numeric_suffix() {
+        let mem = Arc::new(
+            MemFileSystem::new()
+                .with_file("./report.txt", 1)
+                .with_file("./report (2023_01_01 10_00_00 UTC).txt", 1),
+        );
+        let fs: Arc<dyn FileSystem> = mem.clone();
+
+        // first run: the duplicate is moved into the (freshly created) to_delete folder
+        let mut first_map = HashMap::new();
+        put_multi_map(
+            &mut first_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_01_01 10_00_00 UTC).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+        run(test_opt(), fs.clone(), "./", first_map);
+
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_01_01 10_00_00 UTC).txt"));
+
+        // a later run finds a new duplicate carrying the exact same name (File History
+        // timestamps only have one-second resolution, so this does happen); it must not
+        // clobber the file already sitting in the to_delete folder from the first run
+        mem.add_file("./report (2023_01_01 10_00_00 UTC).txt", 1);
+        let mut second_map = HashMap::new();
+        put_multi_map(
+            &mut second_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_01_01 10_00_00 UTC).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+        run(test_opt(), fs.clone(), "./", second_map);
+
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_01_01 10_00_00 UTC).txt"));
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_01_01 10_00_00 UTC) (1).txt"));
+    }
+
+    #[test]
+    fn timestamp_ties_keep_exactly_one_copy() {
+        let fs: Arc<dyn FileSystem> = Arc::new(
+            MemFileSystem::new()
+                .with_file("./report (2023_01_01 10_00_00 UTC).txt", 1)
+                .with_file("./report (2023_01_01 10_00_00 UTC) (1).txt", 1),
+        );
+
+        let mut fh_files_map = HashMap::new();
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_01_01 10_00_00 UTC).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+        put_multi_map(
+            &mut fh_files_map,
+            String::from("report.txt"),
+            fh_file(
+                None,
+                "report (2023_01_01 10_00_00 UTC) (1).txt",
+                "2023_01_01 10_00_00",
+            ),
+        );
+
+        run(test_opt(), fs.clone(), "./", fh_files_map);
+
+        // exactly one of the two tied versions was kept and renamed, the other moved
+        assert!(fs.exists("./report.txt"));
+        assert!(fs.exists("./fhcleanup_to_del/"));
+    }
+
+    #[test]
+    fn keep_n_retains_multiple_recent_versions() {
+        let fs: Arc<dyn FileSystem> = Arc::new(
+            MemFileSystem::new()
+                .with_file("./report (2023_01_01 10_00_00 UTC).txt", 1)
+                .with_file("./report (2023_03_01 10_00_00 UTC).txt", 1)
+                .with_file("./report (2023_06_01 10_00_00 UTC).txt", 1),
+        );
+
+        let mut fh_files_map = HashMap::new();
+        for (full_name, date) in [
+            ("report (2023_01_01 10_00_00 UTC).txt", "2023_01_01 10_00_00"),
+            ("report (2023_03_01 10_00_00 UTC).txt", "2023_03_01 10_00_00"),
+            ("report (2023_06_01 10_00_00 UTC).txt", "2023_06_01 10_00_00"),
+        ] {
+            put_multi_map(
+                &mut fh_files_map,
+                String::from("report.txt"),
+                fh_file(None, full_name, date),
+            );
+        }
+
+        let mut opt = test_opt();
+        opt.keep = 2;
+        run(opt, fs.clone(), "./", fh_files_map);
+
+        // the two newest versions are kept with their timestamped names, only the
+        // oldest is moved to the to_delete folder
+        assert!(fs.exists("./report (2023_03_01 10_00_00 UTC).txt"));
+        assert!(fs.exists("./report (2023_06_01 10_00_00 UTC).txt"));
+        assert!(!fs.exists("./report.txt"));
+        assert!(fs.exists("./fhcleanup_to_del/report (2023_01_01 10_00_00 UTC).txt"));
+    }
+
+    // `--verify-hash` needs real byte content to hash, which `MemFileSystem` does not model,
+    // so this one test of the feature runs against an actual scratch directory via
+    // `StdFileSystem` instead of the in-memory backend used everywhere else.
+    #[test]
+    fn verify_hash_checks_against_existing_target_not_newest_found() {
+        let dir = std::env::temp_dir().join(format!(
+            "fhcleanup_test_verify_hash_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let current_path = dir.to_str().unwrap().to_string() + "/";
+
+        let kept_path = current_path.clone() + "report.txt";
+        let identical_path = current_path.clone() + "report (2023_01_01 10_00_00 UTC).txt";
+        let differing_path = current_path.clone() + "report (2023_06_01 10_00_00 UTC).txt";
+
+        fs::write(&kept_path, b"kept content").unwrap();
+        fs::write(&identical_path, b"kept content").unwrap();
+        fs::write(&differing_path, b"a real, different edit").unwrap();
+
+        let mut fh_files_map = HashMap::new();
+        for path in [&identical_path, &differing_path] {
+            let size = fs::metadata(path).unwrap().len();
+            let full_name = Path::new(path)
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            let date = if path == &identical_path {
+                "2023_01_01 10_00_00"
+            } else {
+                "2023_06_01 10_00_00"
+            };
+            put_multi_map(
+                &mut fh_files_map,
+                String::from("report.txt"),
+                FHFile {
+                    date: NaiveDateTime::parse_from_str(date, "%Y_%m_%d %H_%M_%S").unwrap(),
+                    dir_path: Some(current_path.clone()),
+                    full_name,
+                    size,
+                    hash: RefCell::new(None),
+                },
+            );
+        }
+
+        let mut opt = test_opt();
+        opt.verify_hash = true;
+        // purge rather than move, so this test only exercises the hash comparison itself
+        // and not the platform-specific atomic-rename path of `StdFileSystem`
+        opt.purge = true;
+        let fs: Arc<dyn FileSystem> = Arc::new(StdFileSystem);
+        run(opt, fs, &current_path, fh_files_map);
+
+        // identical to the pre-existing kept file: treated as a true duplicate and deleted
+        assert!(!Path::new(&identical_path).exists());
+        // a real edit, even though it is the newest *found* duplicate: must be verified
+        // against the actually kept file, not the newest duplicate, and therefore left alone
+        assert!(Path::new(&differing_path).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
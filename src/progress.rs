@@ -0,0 +1,87 @@
+use crate::{DELETED_COUNT, MOVED_COUNT, RENAMED_COUNT};
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const TICK_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+const RENDER_INTERVAL: Duration = Duration::from_millis(200);
+
+enum ProgressEvent {
+    DirEntered,
+    FileScanned,
+    FileActedOn,
+}
+
+/// handed to worker tasks so they can report progress without knowing anything about
+/// how (or whether) it gets rendered
+#[derive(Clone)]
+pub struct ProgressReporter {
+    sender: Sender<ProgressEvent>,
+}
+
+impl ProgressReporter {
+    pub fn dir_entered(&self) {
+        let _ = self.sender.send(ProgressEvent::DirEntered);
+    }
+
+    pub fn file_scanned(&self) {
+        let _ = self.sender.send(ProgressEvent::FileScanned);
+    }
+
+    pub fn file_acted_on(&self) {
+        let _ = self.sender.send(ProgressEvent::FileActedOn);
+    }
+}
+
+/// spawns the reporter thread and returns a `ProgressReporter` to clone into worker tasks
+/// plus a `JoinHandle` to wait on once the run is done, so its output is fully drained
+/// before the final summary is printed.
+///
+/// `render` controls whether the reporter actually draws the spinner line: when `-v`
+/// logging is already active the two would otherwise fight over the same terminal line,
+/// so the reporter keeps tallying silently instead.
+pub fn spawn(render: bool) -> (ProgressReporter, JoinHandle<()>) {
+    let (sender, receiver) = mpsc::channel();
+    let join_handle = std::thread::spawn(move || {
+        let mut dirs_entered = 0u64;
+        let mut files_scanned = 0u64;
+        let mut tick = 0usize;
+
+        loop {
+            match receiver.recv_timeout(RENDER_INTERVAL) {
+                Ok(ProgressEvent::DirEntered) => dirs_entered += 1,
+                Ok(ProgressEvent::FileScanned) => files_scanned += 1,
+                Ok(ProgressEvent::FileActedOn) => {}
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if render {
+                render_line(tick, dirs_entered, files_scanned);
+                tick = (tick + 1) % TICK_FRAMES.len();
+            }
+        }
+
+        if render {
+            render_line(tick, dirs_entered, files_scanned);
+            println!();
+        }
+    });
+
+    (ProgressReporter { sender }, join_handle)
+}
+
+fn render_line(tick: usize, dirs_entered: u64, files_scanned: u64) {
+    print!(
+        "\r{} dirs: {} scanned: {} moved: {} deleted: {} renamed: {}   ",
+        TICK_FRAMES[tick],
+        dirs_entered,
+        files_scanned,
+        MOVED_COUNT.load(Ordering::SeqCst),
+        DELETED_COUNT.load(Ordering::SeqCst),
+        RENAMED_COUNT.load(Ordering::SeqCst),
+    );
+    let _ = std::io::stdout().flush();
+}